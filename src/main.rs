@@ -11,14 +11,19 @@ use serde_json::json;
 
 use regex::Regex;
 
+use chrono::{NaiveDate, TimeZone};
+
 type JsonMap = serde_json::Map<String, serde_json::Value>;
 
+const MORE_MARKER: &str = "<!-- more -->";
+
 type Directory = Vec<(String, Node)>;
 
 #[derive(Debug)]
 enum Node {
     Page(String),
     Dir(Directory),
+    Asset(PathBuf),
 }
 
 handlebars_helper!(lt: |left: u16, right: u16| {
@@ -44,6 +49,39 @@ struct Config {
     blog: String,
     ///paths to exclude
     ignore: Option<Vec<String>>,
+    ///name of the template used to render a single tag's page; receives `{tag, slug, posts}`.
+    ///when unset, no taxonomy pages are generated
+    taxonomy_template: Option<String>,
+    ///name of the template used to render the top-level tag listing page; receives
+    ///`{tags: [{tag, slug, count}, ...]}`. when unset, no listing page is generated even if
+    ///`taxonomy_template` is set
+    taxonomy_list_template: Option<String>,
+    ///path, relative to the target directory, where tag pages are written
+    tags_path: Option<String>,
+    ///RSS feed settings; when unset, no feed is generated
+    feed: Option<FeedConfig>,
+    ///external commands that transform Markdown before it is rendered, in order
+    preprocessors: Option<Vec<PreprocessorConfig>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct PreprocessorConfig {
+    ///command to invoke
+    cmd: String,
+    ///arguments passed to the command
+    args: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct FeedConfig {
+    ///path, relative to the target directory, where the feed is written
+    output: String,
+    ///site title used as the feed's title
+    title: String,
+    ///base URL used to build absolute links for feed items
+    base_url: String,
+    ///feed author name
+    author: String,
 }
 
 fn main() -> Result<()> {
@@ -64,15 +102,44 @@ fn main() -> Result<()> {
     register_templates_dir(PathBuf::from(&config.templates), &mut handlebars)?;
 
     log::info!("processing blog posts");
+    let posts = process_blog_posts(PathBuf::from(&config.blog))?;
+    let tags = build_tag_index(&posts);
     let globals = json!({
-        "posts": process_blog_posts(PathBuf::from(&config.blog))?
+        "posts": posts.clone(),
+        "taxonomies": { "tags": tags }
     });
 
     log::info!("compiling site");
     let mut ignore = Vec::<String>::new();
     ignore.append(&mut config.ignore.unwrap_or_default());
     ignore.push(config.templates);
-    let site = compile_dir(PathBuf::from(&config.source), &globals, &ignore, handlebars)?;
+    let preprocessors = config.preprocessors.unwrap_or_default();
+    let mut site = compile_dir(
+        PathBuf::from(&config.source),
+        &globals,
+        &ignore,
+        &preprocessors,
+        handlebars.clone(),
+    )?;
+
+    if let Some(taxonomy_template) = &config.taxonomy_template {
+        log::info!("generating taxonomy pages");
+        let tags_dir = build_taxonomy_pages(
+            &tags,
+            taxonomy_template,
+            config.taxonomy_list_template.as_deref(),
+            &handlebars,
+        )?;
+        let tags_path = config.tags_path.unwrap_or_else(|| "tags".to_owned());
+        site.push((tags_path, Node::Dir(tags_dir)));
+    }
+
+    if let Some(feed_config) = &config.feed {
+        log::info!("generating feed");
+        let xml = build_feed(&posts, feed_config)?;
+        let (name, node) = node_at_path(Path::new(&feed_config.output), Node::Page(xml));
+        site.push((name, node));
+    }
 
     log::info!("writing site to filesystem");
     emit_directory(site, PathBuf::from(&config.target))?;
@@ -84,6 +151,7 @@ fn compile_dir<T: AsRef<std::path::Path>>(
     path: T,
     globals: &JsonValue,
     ignore: &Vec<String>,
+    preprocessors: &[PreprocessorConfig],
     handlebars: Handlebars,
 ) -> Result<Directory> {
     let path = PathBuf::from(path.as_ref()).canonicalize()?;
@@ -109,11 +177,13 @@ fn compile_dir<T: AsRef<std::path::Path>>(
         }
         if meta.is_file() {
             log::debug!("processing file: {entry_path:?}");
-            if let Some((html, out_name)) = compile_file(&entry_path, globals, &handlebars)? {
-                directory.push((out_name, Node::Page(html)));
+            if let Some((node, out_name)) =
+                compile_file(&entry_path, globals, preprocessors, &handlebars)?
+            {
+                directory.push((out_name, node));
             }
         } else if meta.is_dir() {
-            let dir = compile_dir(&entry_path, globals, ignore, handlebars.clone())?;
+            let dir = compile_dir(&entry_path, globals, ignore, preprocessors, handlebars.clone())?;
             directory.push((file_name.to_owned(), Node::Dir(dir)));
         } else {
             log::debug!("neither file nor directory; skipping");
@@ -125,26 +195,41 @@ fn compile_dir<T: AsRef<std::path::Path>>(
 fn compile_file(
     path: impl AsRef<Path>,
     globals: &JsonValue,
+    preprocessors: &[PreprocessorConfig],
     handlebars: &Handlebars,
-) -> Result<Option<(String, String)>> {
+) -> Result<Option<(Node, String)>> {
     let path = path.as_ref();
-    match get_file_ext(path)? {
-        "md" => Ok(compile_markdown(path, globals, handlebars)?),
+    match get_file_ext(path) {
+        Ok("md") => Ok(compile_markdown(path, globals, preprocessors, handlebars)?
+            .map(|(html, out_name)| (Node::Page(html), out_name))),
         _ => {
-            log::debug!("unhandled file extension for {path:?}");
-            Ok(None)
+            log::debug!("copying unhandled or extensionless file verbatim: {path:?}");
+            let out_name = get_file_name(path)?.to_owned();
+            Ok(Some((Node::Asset(path.to_owned()), out_name)))
         }
     }
 }
 
+fn extract_summary(body: &str) -> Option<String> {
+    body.split_once(MORE_MARKER)
+        .map(|(before, _after)| markdown::to_html(before))
+}
+
+fn render_content(body: &str) -> String {
+    markdown::to_html(&body.replace(MORE_MARKER, ""))
+}
+
 fn compile_markdown(
     path: impl AsRef<Path>,
     globals: &JsonValue,
+    preprocessors: &[PreprocessorConfig],
     handlebars: &Handlebars,
 ) -> Result<Option<(String, String)>> {
     let path = path.as_ref();
     let (fm, mut md): (JsonValue, String) = split_frontmatter(path)?;
     let mut fm = replace_globals(fm, globals);
+    md = run_preprocessors(md, &mut fm, preprocessors)
+        .context(format!("preprocessing {}", path.display()))?;
     md = replace_uuid_links(md, globals).context(format!("processing {}", path.display()))?;
     let tmpl_name: String = fm
         .get("template")
@@ -152,7 +237,10 @@ fn compile_markdown(
         .as_str()
         .expect("expected template name to be string")
         .to_owned();
-    let content = markdown::to_html(&md);
+    if let Some(summary) = extract_summary(&md) {
+        fm.insert(String::from("summary"), json!(summary));
+    }
+    let content = render_content(&md);
     fm.insert(String::from("content"), json!(content));
     let html: String = handlebars.render(&tmpl_name, &fm).context("{path:?}")?;
     let mut out_name = get_file_stem(path)?.to_owned();
@@ -176,6 +264,68 @@ fn replace_globals(obj: JsonValue, globals: &JsonValue) -> JsonMap {
     new_obj
 }
 
+fn run_preprocessors(
+    mut content: String,
+    fm: &mut JsonMap,
+    preprocessors: &[PreprocessorConfig],
+) -> Result<String> {
+    for preprocessor in preprocessors {
+        if !preprocessor_supports_protocol(preprocessor)? {
+            log::info!(
+                "skipping preprocessor {:?}: did not acknowledge the plugin protocol",
+                preprocessor.cmd
+            );
+            continue;
+        }
+        let envelope = json!({ "frontmatter": fm, "content": content }).to_string();
+        let mut child = std::process::Command::new(&preprocessor.cmd)
+            .args(preprocessor.args.clone().unwrap_or_default())
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .context(format!("spawning preprocessor {:?}", preprocessor.cmd))?;
+        let mut stdin = child.stdin.take().expect("stdin to be piped");
+        let writer = std::thread::spawn(move || -> std::io::Result<()> {
+            use std::io::Write;
+            stdin.write_all(envelope.as_bytes())
+        });
+        let output = child
+            .wait_with_output()
+            .context(format!("running preprocessor {:?}", preprocessor.cmd))?;
+        if !output.status.success() {
+            bail!(
+                "preprocessor {:?} exited with {}",
+                preprocessor.cmd,
+                output.status
+            );
+        }
+        writer
+            .join()
+            .expect("preprocessor stdin writer thread panicked")
+            .context(format!("writing stdin for preprocessor {:?}", preprocessor.cmd))?;
+        let result: JsonValue = serde_json::from_slice(&output.stdout)
+            .context(format!("parsing output of preprocessor {:?}", preprocessor.cmd))?;
+        if let Some(new_content) = result.get("content").and_then(|v| v.as_str()) {
+            content = new_content.to_owned();
+        }
+        if let Some(new_fm) = result.get("frontmatter").and_then(|v| v.as_object()) {
+            fm.extend(new_fm.clone());
+        }
+    }
+    Ok(content)
+}
+
+fn preprocessor_supports_protocol(preprocessor: &PreprocessorConfig) -> Result<bool> {
+    let output = std::process::Command::new(&preprocessor.cmd)
+        .arg("supports")
+        .output()
+        .context(format!(
+            "invoking supports handshake for preprocessor {:?}",
+            preprocessor.cmd
+        ))?;
+    Ok(output.status.success())
+}
+
 fn replace_uuid_links(mut text: String, globals: &JsonValue) -> Result<String> {
     let mut new_text = text.clone();
     let re = Regex::new(r"\[[^\]]+\]\(:([a-zA-Z0-9]+)\)").unwrap();
@@ -233,6 +383,23 @@ fn split_frontmatter(path: impl AsRef<Path>) -> Result<(JsonValue, String)> {
     Ok((options, data.to_owned()))
 }
 
+fn node_at_path(path: impl AsRef<Path>, leaf: Node) -> (String, Node) {
+    let mut parts: Vec<String> = path
+        .as_ref()
+        .components()
+        .filter_map(|part| match part {
+            std::path::Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    let file_name = parts.pop().expect("path to have a file name");
+    let mut entry = (file_name, leaf);
+    for part in parts.into_iter().rev() {
+        entry = (part, Node::Dir(vec![entry]));
+    }
+    entry
+}
+
 fn emit_directory(dir: Directory, target: impl AsRef<Path>) -> Result<()> {
     for (path, node) in dir {
         let mut target = PathBuf::from(target.as_ref());
@@ -254,6 +421,10 @@ fn emit_directory(dir: Directory, target: impl AsRef<Path>) -> Result<()> {
                 std::fs::create_dir_all(&target)?;
                 emit_directory(dir, &target)?;
             }
+            Node::Asset(src) => {
+                log::debug!("copying asset from {src:?}");
+                std::fs::copy(src, target)?;
+            }
         }
     }
     Ok(())
@@ -281,6 +452,157 @@ fn register_templates_dir(path: impl AsRef<Path>, handlebars: &mut Handlebars) -
     Ok(())
 }
 
+/// Maps a tag's slug to its display label (the first-seen spelling) and the
+/// posts filed under it. Tags are bucketed by slug rather than raw label so
+/// that case/punctuation variants of the same tag (e.g. "Rust" and "rust")
+/// land in the same bucket instead of silently shadowing one another.
+fn build_tag_index(posts: &[JsonValue]) -> JsonMap {
+    let mut tags: JsonMap = JsonMap::new();
+    for post in posts {
+        let post_tags = match post.get("tags").and_then(|t| t.as_array()) {
+            Some(post_tags) => post_tags,
+            None => continue,
+        };
+        let entry = json!({
+            "title": post.get("title"),
+            "link": post.get("link"),
+            "date": post.get("date"),
+        });
+        for tag in post_tags {
+            let tag = match tag.as_str() {
+                Some(tag) => tag,
+                None => continue,
+            };
+            let slug = slugify(tag);
+            let bucket = tags
+                .entry(slug)
+                .or_insert_with(|| json!({ "label": tag, "posts": [] }));
+            bucket
+                .get_mut("posts")
+                .and_then(|p| p.as_array_mut())
+                .expect("tag bucket to contain a posts array")
+                .push(entry.clone());
+        }
+    }
+    tags
+}
+
+fn build_taxonomy_pages(
+    tags: &JsonMap,
+    template_name: &str,
+    list_template_name: Option<&str>,
+    handlebars: &Handlebars,
+) -> Result<Directory> {
+    let mut dir: Directory = vec![];
+    let mut tag_list: Vec<JsonValue> = vec![];
+    for (slug, bucket) in tags {
+        let tag = bucket.get("label").and_then(|l| l.as_str()).unwrap_or(slug);
+        let posts = bucket.get("posts").cloned().unwrap_or_else(|| json!([]));
+        let html = handlebars
+            .render(template_name, &json!({ "tag": tag, "slug": slug, "posts": posts }))
+            .context(format!("rendering tag page for {tag:?}"))?;
+        let mut out_name = slug.clone();
+        out_name.push_str(".html");
+        dir.push((out_name, Node::Page(html)));
+        let count = posts.as_array().map(|p| p.len()).unwrap_or(0);
+        tag_list.push(json!({ "tag": tag, "slug": slug, "count": count }));
+    }
+    if let Some(list_template_name) = list_template_name {
+        let listing_html = handlebars
+            .render(list_template_name, &json!({ "tags": tag_list }))
+            .context("rendering tag listing page")?;
+        dir.push(("index.html".to_owned(), Node::Page(listing_html)));
+    }
+    Ok(dir)
+}
+
+fn slugify(tag: &str) -> String {
+    let mut slug = String::new();
+    for ch in tag.chars() {
+        if ch.is_alphanumeric() {
+            slug.extend(ch.to_lowercase());
+        } else if !slug.ends_with('-') && !slug.is_empty() {
+            slug.push('-');
+        }
+    }
+    if slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
+}
+
+fn reading_stats(body: &str) -> (usize, u64) {
+    let word_count = body.split_whitespace().count();
+    let reading_time = (word_count as f64 / 200.0).ceil().max(1.0) as u64;
+    (word_count, reading_time)
+}
+
+fn strip_date_prefix(stem: &str) -> (Option<&str>, &str) {
+    let date_prefix = Regex::new(
+        r"^(\d{4})-(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01])(?:T\d{2}:\d{2}:\d{2}(?:Z|[+-]\d{2}:\d{2})?)?[_-]",
+    )
+    .unwrap();
+    match date_prefix.find(stem) {
+        Some(m) => {
+            let date = &m.as_str()[..m.as_str().len() - 1];
+            (Some(date), &stem[m.end()..])
+        }
+        None => (None, stem),
+    }
+}
+
+fn build_feed(posts: &[JsonValue], feed: &FeedConfig) -> Result<String> {
+    let mut items = String::new();
+    for post in posts {
+        let title = post.get("title").and_then(|v| v.as_str()).unwrap_or_default();
+        let link = post.get("link").and_then(|v| v.as_str()).unwrap_or_default();
+        let date = post.get("date").and_then(|v| v.as_str()).unwrap_or_default();
+        let body_html = match post.get("summary").and_then(|v| v.as_str()) {
+            Some(summary) => summary.to_owned(),
+            None => post
+                .get("content")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_owned(),
+        };
+        let absolute_link = format!("{}{link}", feed.base_url.trim_end_matches('/'));
+        let pub_date = format_rfc822_date(date).context(format!("building feed item for {link:?}"))?;
+        let body_html = escape_cdata(&body_html);
+        items.push_str(&format!(
+            "    <item>\n      <title>{}</title>\n      <link>{absolute_link}</link>\n      <guid>{absolute_link}</guid>\n      <pubDate>{pub_date}</pubDate>\n      <description><![CDATA[{body_html}]]></description>\n    </item>\n",
+            escape_xml(title),
+        ));
+    }
+    Ok(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n  <channel>\n    <title>{}</title>\n    <link>{}</link>\n    <description>{}</description>\n    <managingEditor>{}</managingEditor>\n{items}  </channel>\n</rss>\n",
+        escape_xml(&feed.title),
+        feed.base_url,
+        escape_xml(&feed.title),
+        escape_xml(&feed.author),
+    ))
+}
+
+fn format_rfc822_date(date: &str) -> Result<String> {
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(date) {
+        return Ok(dt.format("%a, %d %b %Y %H:%M:%S %z").to_string());
+    }
+    let naive = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .context(format!("could not parse date {date:?} for feed"))?;
+    let dt = chrono::Utc
+        .from_utc_datetime(&naive.and_hms_opt(0, 0, 0).expect("midnight to be valid"));
+    Ok(dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn escape_cdata(s: &str) -> String {
+    s.replace("]]>", "]]]]><![CDATA[>")
+}
+
 fn process_blog_posts(blog_dir: impl AsRef<Path>) -> Result<Vec<JsonValue>> {
     let blog_dir = blog_dir.as_ref().canonicalize()?;
     let mut posts = Vec::new();
@@ -318,11 +640,18 @@ fn process_blog_posts_dir(
                 continue;
             }
             if get_file_ext(&path)? == "md" {
-                let (mut fm, _): (JsonValue, _) = split_frontmatter(&path)?;
+                let (mut fm, body): (JsonValue, String) = split_frontmatter(&path)?;
                 let fm = fm.as_object_mut().unwrap();
+                let stem = get_file_stem(&path)?;
+                let (date_from_filename, stem) = strip_date_prefix(stem);
+                if let Some(date) = date_from_filename {
+                    if !fm.contains_key("date") {
+                        fm.insert("date".to_owned(), json!(date));
+                    }
+                }
                 ensure_key(fm, "date", "str")
                     .context(format!("processing file {}", path.display()))?;
-                let mut out_name = get_file_stem(&path)?.to_owned();
+                let mut out_name = stem.to_owned();
                 out_name.push_str(".html");
                 let mut link = PathBuf::new();
                 link.push("/blog");
@@ -330,6 +659,13 @@ fn process_blog_posts_dir(
                 link.push(&out_name);
                 log::debug!("link is {link:?}");
                 fm.insert("link".to_owned(), json!(link));
+                let (word_count, reading_time) = reading_stats(&body);
+                fm.insert("word_count".to_owned(), json!(word_count));
+                fm.insert("reading_time".to_owned(), json!(reading_time));
+                fm.insert("content".to_owned(), json!(render_content(&body)));
+                if let Some(summary) = extract_summary(&body) {
+                    fm.insert("summary".to_owned(), json!(summary));
+                }
                 posts.push(json! {fm});
             }
         }
@@ -473,4 +809,98 @@ mod tests {
         );
         assert_eq!(got, expect)
     }
+
+    #[test]
+    fn slugify_collapses_separators() {
+        assert_eq!(slugify("Rust & WebAssembly"), "rust-webassembly");
+        assert_eq!(slugify("--leading and trailing--"), "leading-and-trailing");
+        assert_eq!(slugify("multiple   spaces"), "multiple-spaces");
+    }
+
+    #[test]
+    fn build_tag_index_merges_case_variant_tags_into_one_bucket() {
+        let posts = vec![
+            json!({"title": "a", "link": "/a", "date": "2023-01-01", "tags": ["Rust"]}),
+            json!({"title": "b", "link": "/b", "date": "2023-01-02", "tags": ["rust"]}),
+        ];
+        let tags = build_tag_index(&posts);
+        assert_eq!(tags.len(), 1);
+        let bucket = tags.get("rust").expect("rust bucket to exist");
+        assert_eq!(bucket.get("label").and_then(|l| l.as_str()), Some("Rust"));
+        assert_eq!(bucket.get("posts").and_then(|p| p.as_array()).map(|p| p.len()), Some(2));
+    }
+
+    #[test]
+    fn reading_stats_rounds_up_and_clamps_to_one_minute() {
+        assert_eq!(reading_stats("").0, 0);
+        assert_eq!(reading_stats("").1, 1);
+        let two_hundred_words = "word ".repeat(200);
+        assert_eq!(reading_stats(&two_hundred_words).1, 1);
+        let two_hundred_and_one_words = "word ".repeat(201);
+        assert_eq!(reading_stats(&two_hundred_and_one_words).1, 2);
+    }
+
+    #[test]
+    fn extract_summary_splits_at_more_marker() {
+        let body = "intro paragraph\n\n<!-- more -->\n\nrest of the post";
+        let summary = extract_summary(body).expect("summary to be present");
+        assert!(summary.contains("intro paragraph"));
+        assert!(!summary.contains("rest of the post"));
+    }
+
+    #[test]
+    fn extract_summary_is_none_without_marker() {
+        assert_eq!(extract_summary("no marker here"), None);
+    }
+
+    #[test]
+    fn render_content_strips_the_more_marker() {
+        let body = "intro paragraph\n\n<!-- more -->\n\nrest of the post";
+        let content = render_content(body);
+        assert!(!content.contains("more"));
+        assert!(content.contains("intro paragraph"));
+        assert!(content.contains("rest of the post"));
+    }
+
+    #[test]
+    fn strip_date_prefix_extracts_date_and_cleans_stem() {
+        let (date, stem) = strip_date_prefix("2023-05-14-my-post");
+        assert_eq!(date, Some("2023-05-14"));
+        assert_eq!(stem, "my-post");
+
+        let (date, stem) = strip_date_prefix("2023-05-14_my_post");
+        assert_eq!(date, Some("2023-05-14"));
+        assert_eq!(stem, "my_post");
+
+        let (date, stem) = strip_date_prefix("my-post");
+        assert_eq!(date, None);
+        assert_eq!(stem, "my-post");
+
+        let (date, stem) = strip_date_prefix("2023-13-01-bad-month");
+        assert_eq!(date, None);
+        assert_eq!(stem, "2023-13-01-bad-month");
+    }
+
+    #[test]
+    fn format_rfc822_date_handles_rfc3339_and_plain_dates() -> Result<()> {
+        assert_eq!(
+            format_rfc822_date("2023-05-14T09:30:00Z")?,
+            "Sun, 14 May 2023 09:30:00 +0000"
+        );
+        assert_eq!(
+            format_rfc822_date("2023-05-14")?,
+            "Sun, 14 May 2023 00:00:00 GMT"
+        );
+        assert!(format_rfc822_date("not a date").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn escape_cdata_breaks_out_of_embedded_cdata_terminators() {
+        assert_eq!(
+            escape_cdata("a ]]> b"),
+            "a ]]]]><![CDATA[> b"
+        );
+        assert_eq!(escape_cdata("no marker here"), "no marker here");
+    }
 }